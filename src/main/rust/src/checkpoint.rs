@@ -0,0 +1,272 @@
+//! Binary checkpoint format for the aggregated per-station map.
+//!
+//! Layout: an 8-byte magic signature, a version byte, a little-endian record
+//! count, then each record as `[u16 name_len][name bytes][i32 min][i32 max]
+//! [i64 sum][u32 count]`, and finally a 4-byte CRC32C over everything after
+//! the signature. The first magic byte is non-ASCII so a text-mode transfer
+//! that strips the high bit is caught at the signature check; other changes
+//! to the bytes after it, such as rewritten line endings, fail the CRC32C.
+
+use std::hash::BuildHasherDefault;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use hashbrown::HashMap;
+use nohash::NoHashHasher;
+
+use crate::{Record, StationSlot};
+
+pub const MAGIC: [u8; 8] = [0x8b, b'1', b'b', b'r', b'c', b'c', b'k', b'p'];
+pub const VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    BadMagic([u8; 8]),
+    UnsupportedVersion(u8),
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The body ended before a length-prefixed or fixed-width field it
+    /// promised (via `count` or `name_len`) could be fully read. The CRC
+    /// check only proves the bytes present are self-consistent, not that
+    /// there are enough of them, so this is checked independently.
+    Truncated,
+    InvalidStationName(std::str::Utf8Error),
+}
+
+impl std::fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "{err}"),
+            CheckpointError::BadMagic(got) => write!(f, "bad checkpoint magic: {got:?}"),
+            CheckpointError::UnsupportedVersion(got) => {
+                write!(f, "unsupported checkpoint version: {got}")
+            }
+            CheckpointError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checkpoint CRC32C mismatch: expected {expected:08x}, got {actual:08x}"
+            ),
+            CheckpointError::Truncated => write!(f, "checkpoint body is truncated"),
+            CheckpointError::InvalidStationName(err) => {
+                write!(f, "checkpoint station name is not valid utf8: {err}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+type StationMapInner = HashMap<u64, StationSlot, BuildHasherDefault<NoHashHasher<u64>>>;
+
+/// Consumes and returns the next `n` bytes from `cursor`, or
+/// `CheckpointError::Truncated` if fewer than `n` remain.
+fn take<'a>(cursor: &mut &'a [u8], n: usize) -> Result<&'a [u8], CheckpointError> {
+    if cursor.len() < n {
+        return Err(CheckpointError::Truncated);
+    }
+
+    let (head, tail) = cursor.split_at(n);
+    *cursor = tail;
+
+    Ok(head)
+}
+
+/// Serializes `map` into the checkpoint format described above. Every
+/// `Record` in every slot's overflow chain is written out as its own entry,
+/// so a checkpoint round-trip reproduces any collisions intact.
+pub fn write_binary(map: &StationMapInner, mut w: impl Write) -> Result<(), CheckpointError> {
+    let mut body = Vec::new();
+    let record_count: usize = map.values().map(|slot| slot.records.len()).sum();
+
+    body.push(VERSION);
+    body.extend_from_slice(&(record_count as u64).to_le_bytes());
+
+    for record in map.values().flat_map(|slot| &slot.records) {
+        let name_bytes = record.station_name.as_bytes();
+        body.extend_from_slice(&(name_bytes.len() as u16).to_le_bytes());
+        body.extend_from_slice(name_bytes);
+        body.extend_from_slice(&record.values.min.to_le_bytes());
+        body.extend_from_slice(&record.values.max.to_le_bytes());
+        body.extend_from_slice(&record.values.sum.to_le_bytes());
+        body.extend_from_slice(&record.values.count.to_le_bytes());
+    }
+
+    let crc = crc32c::crc32c(&body);
+
+    w.write_all(&MAGIC)?;
+    w.write_all(&body)?;
+    w.write_all(&crc.to_le_bytes())?;
+
+    Ok(())
+}
+
+/// Reads a checkpoint written by `write_binary`, validating the magic,
+/// version, and CRC32C before folding any of its records into `map`.
+/// Colliding names are appended to the slot's overflow chain rather than
+/// overwriting, the same as the live aggregation path.
+pub fn merge_binary(
+    map: &mut StationMapInner,
+    mut r: impl Read,
+    collisions: &AtomicU64,
+) -> Result<(), CheckpointError> {
+    let mut contents = Vec::new();
+    r.read_to_end(&mut contents)?;
+
+    if contents.len() < MAGIC.len() + 4 {
+        return Err(CheckpointError::Truncated);
+    }
+
+    let (magic, rest) = contents.split_at(MAGIC.len());
+
+    if magic != MAGIC {
+        let mut got = [0u8; 8];
+        got.copy_from_slice(magic);
+        return Err(CheckpointError::BadMagic(got));
+    }
+
+    let (body, crc_bytes) = rest.split_at(rest.len() - 4);
+    let expected_crc = u32::from_le_bytes(crc_bytes.try_into().unwrap());
+    let actual_crc = crc32c::crc32c(body);
+
+    if expected_crc != actual_crc {
+        return Err(CheckpointError::ChecksumMismatch {
+            expected: expected_crc,
+            actual: actual_crc,
+        });
+    }
+
+    let mut cursor = body;
+
+    let version = take(&mut cursor, 1)?[0];
+
+    if version != VERSION {
+        return Err(CheckpointError::UnsupportedVersion(version));
+    }
+
+    let record_count = u64::from_le_bytes(take(&mut cursor, 8)?.try_into().unwrap());
+
+    for _ in 0..record_count {
+        let name_len = u16::from_le_bytes(take(&mut cursor, 2)?.try_into().unwrap()) as usize;
+        let name = std::str::from_utf8(take(&mut cursor, name_len)?)
+            .map_err(CheckpointError::InvalidStationName)?;
+
+        let min = i32::from_le_bytes(take(&mut cursor, 4)?.try_into().unwrap());
+        let max = i32::from_le_bytes(take(&mut cursor, 4)?.try_into().unwrap());
+        let sum = i64::from_le_bytes(take(&mut cursor, 8)?.try_into().unwrap());
+        let count = u32::from_le_bytes(take(&mut cursor, 4)?.try_into().unwrap());
+
+        let uuid = Record::uuid(name);
+        let incoming = Record::from_parts(name, crate::StationValues { min, max, sum, count });
+
+        match map.get_mut(&uuid) {
+            Some(slot) => {
+                if slot.merge_one(&incoming) {
+                    collisions.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            None => unsafe {
+                map.insert_unique_unchecked(uuid, StationSlot::new(incoming));
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StationValues;
+
+    fn empty_map() -> StationMapInner {
+        HashMap::with_hasher(nohash::BuildNoHashHasher::new())
+    }
+
+    fn insert(map: &mut StationMapInner, name: &str, values: StationValues) {
+        let record = Record::from_parts(name, values);
+        let uuid = Record::uuid(name);
+
+        unsafe {
+            map.insert_unique_unchecked(uuid, StationSlot::new(record));
+        }
+    }
+
+    fn values(min: i32, max: i32, sum: i64, count: u32) -> StationValues {
+        StationValues { min, max, sum, count }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut map = empty_map();
+        insert(&mut map, "Hamburg", values(-50, 300, 1234, 7));
+        insert(&mut map, "Zurich", values(-999, 999, -42, 3));
+
+        let mut bytes = Vec::new();
+        write_binary(&map, &mut bytes).expect("write_binary should succeed");
+
+        let mut restored = empty_map();
+        let collisions = AtomicU64::new(0);
+        merge_binary(&mut restored, &bytes[..], &collisions).expect("merge_binary should succeed");
+
+        for name in ["Hamburg", "Zurich"] {
+            let original = &map[&Record::uuid(name)].records[0];
+            let restored_record = &restored[&Record::uuid(name)].records[0];
+
+            assert_eq!(restored_record.station_name.as_ref(), original.station_name.as_ref());
+            assert_eq!(restored_record.values.min, original.values.min);
+            assert_eq!(restored_record.values.max, original.values.max);
+            assert_eq!(restored_record.values.sum, original.values.sum);
+            assert_eq!(restored_record.values.count, original.values.count);
+        }
+
+        assert_eq!(collisions.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn truncated_field_is_rejected() {
+        // A record_count of 1 promising a 5-byte name, but only 3 name bytes
+        // actually follow. The CRC is computed over exactly these (truncated)
+        // bytes, so it passes — the length mismatch must be caught separately.
+        let mut body = Vec::new();
+        body.push(VERSION);
+        body.extend_from_slice(&1u64.to_le_bytes());
+        body.extend_from_slice(&5u16.to_le_bytes());
+        body.extend_from_slice(b"Hel");
+
+        let crc = crc32c::crc32c(&body);
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&body);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+
+        let mut map = empty_map();
+        let collisions = AtomicU64::new(0);
+        let err = merge_binary(&mut map, &bytes[..], &collisions).unwrap_err();
+
+        assert!(matches!(err, CheckpointError::Truncated));
+    }
+
+    #[test]
+    fn checksum_mismatch_is_rejected() {
+        let mut map = empty_map();
+        insert(&mut map, "Paris", values(0, 100, 100, 1));
+
+        let mut bytes = Vec::new();
+        write_binary(&map, &mut bytes).expect("write_binary should succeed");
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let mut restored = empty_map();
+        let collisions = AtomicU64::new(0);
+        let err = merge_binary(&mut restored, &bytes[..], &collisions).unwrap_err();
+
+        assert!(matches!(err, CheckpointError::ChecksumMismatch { .. }));
+    }
+}
@@ -0,0 +1,263 @@
+//! Pluggable file-reading backends for `StationMap`.
+//!
+//! `IoEngine` abstracts the part of the pipeline that turns byte ranges of
+//! the input file into in-memory blocks, so the parsing side never has to
+//! know whether those bytes came from a memory mapping or from io_uring.
+
+use std::fmt;
+use std::fs::File;
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+/// A block of file bytes handed to a parsing worker.
+///
+/// `Mapped` borrows directly from the underlying memory mapping (no copy);
+/// `Owned` holds bytes read into a private buffer, which is what the
+/// io_uring backend produces.
+pub enum Block {
+    Mapped { mmap: Arc<Mmap>, start: usize, end: usize },
+    Owned(Vec<u8>),
+}
+
+impl Block {
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            Block::Mapped { mmap, start, end } => &mmap[*start..*end],
+            Block::Owned(buf) => buf,
+        }
+    }
+}
+
+/// Errors surfaced by an `IoEngine`, kept distinct from `Box<dyn Error>` so
+/// callers can tell a clean end-of-file apart from a short read or an OS
+/// failure worth retrying.
+#[derive(Debug)]
+pub enum IoError {
+    Eof,
+    ShortRead { expected: usize, actual: usize },
+    Os(std::io::Error),
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoError::Eof => write!(f, "end of file"),
+            IoError::ShortRead { expected, actual } => {
+                write!(f, "short read: expected {expected} bytes, got {actual}")
+            }
+            IoError::Os(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for IoError {}
+
+impl From<std::io::Error> for IoError {
+    fn from(err: std::io::Error) -> Self {
+        IoError::Os(err)
+    }
+}
+
+/// A source of file bytes that can be read by offset and length.
+pub trait IoEngine: Send + Sync {
+    /// Total length of the underlying file, in bytes.
+    fn len(&self) -> usize;
+
+    /// Read exactly `len` bytes starting at `offset`.
+    fn read_block(&self, offset: usize, len: usize) -> Result<Block, IoError>;
+}
+
+/// The default backend: `mmap` the whole file once, then hand out borrowed
+/// slices of it.
+pub struct SyncIoEngine {
+    mmap: Arc<Mmap>,
+}
+
+impl SyncIoEngine {
+    pub fn new(file: &File) -> Result<Self, IoError> {
+        let mmap = unsafe { Mmap::map(file) }?;
+
+        Ok(Self { mmap: Arc::new(mmap) })
+    }
+}
+
+impl IoEngine for SyncIoEngine {
+    fn len(&self) -> usize {
+        self.mmap.len()
+    }
+
+    fn read_block(&self, offset: usize, len: usize) -> Result<Block, IoError> {
+        // `offset == self.mmap.len()` is a legitimate zero-length read right
+        // at EOF (e.g. a chunk boundary probe on an empty or small file);
+        // only reject offsets that are actually past the end of the file.
+        if offset > self.mmap.len() {
+            return Err(IoError::Eof);
+        }
+
+        let end = offset.saturating_add(len).min(self.mmap.len());
+
+        if end - offset < len {
+            return Err(IoError::ShortRead {
+                expected: len,
+                actual: end - offset,
+            });
+        }
+
+        Ok(Block::Mapped {
+            mmap: self.mmap.clone(),
+            start: offset,
+            end,
+        })
+    }
+}
+
+/// An io_uring-backed engine that keeps up to `queue_depth` block reads
+/// outstanding at once, so parsing workers calling `read_block` concurrently
+/// never stall waiting on a single in-flight syscall.
+///
+/// The ring is owned by a dedicated I/O thread; `read_block` just posts a
+/// request and blocks on its own reply channel, so any number of worker
+/// threads can call it concurrently while the ring pipelines their reads.
+#[cfg(target_os = "linux")]
+pub struct AsyncIoEngine {
+    file_len: usize,
+    request_tx: std::sync::mpsc::Sender<IoRequest>,
+    _io_thread: std::thread::JoinHandle<()>,
+}
+
+#[cfg(target_os = "linux")]
+struct IoRequest {
+    offset: usize,
+    len: usize,
+    reply_tx: std::sync::mpsc::Sender<Result<Block, IoError>>,
+}
+
+#[cfg(target_os = "linux")]
+impl AsyncIoEngine {
+    /// Constructs the ring synchronously so a failure to init (old kernel,
+    /// io_uring disabled by seccomp/container policy, etc.) is reported here
+    /// as an `Err` the caller can fall back on, instead of surfacing later as
+    /// every `read_block` call failing once the I/O thread has already quit.
+    pub fn new(file: File, queue_depth: u32) -> Result<Self, IoError> {
+        use io_uring::IoUring;
+
+        let file_len = file.metadata()?.len() as usize;
+        let ring = IoUring::new(queue_depth)?;
+        let (request_tx, request_rx) = std::sync::mpsc::channel::<IoRequest>();
+
+        let io_thread = std::thread::spawn(move || {
+            Self::run_io_loop(file, ring, request_rx);
+        });
+
+        Ok(Self {
+            file_len,
+            request_tx,
+            _io_thread: io_thread,
+        })
+    }
+
+    fn run_io_loop(
+        file: File,
+        mut ring: io_uring::IoUring,
+        request_rx: std::sync::mpsc::Receiver<IoRequest>,
+    ) {
+        use std::collections::HashMap;
+        use std::os::unix::io::AsRawFd;
+
+        use io_uring::{opcode, types};
+
+        let mut inflight: HashMap<u64, (Vec<u8>, std::sync::mpsc::Sender<Result<Block, IoError>>)> =
+            HashMap::new();
+        let mut next_user_data = 0u64;
+
+        loop {
+            // Block on the channel only when nothing is in flight; otherwise
+            // take any queued request without blocking, and if there is none,
+            // wait on the ring so every outstanding read gets its reply.
+            let next = if inflight.is_empty() {
+                match request_rx.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => break,
+                }
+            } else {
+                request_rx.try_recv().ok()
+            };
+
+            if let Some(request) = next {
+                let mut buf = vec![0u8; request.len];
+                let user_data = next_user_data;
+                next_user_data += 1;
+
+                let read_e = opcode::Read::new(types::Fd(file.as_raw_fd()), buf.as_mut_ptr(), request.len as u32)
+                    .offset(request.offset as u64)
+                    .build()
+                    .user_data(user_data);
+
+                inflight.insert(user_data, (buf, request.reply_tx));
+
+                unsafe {
+                    while ring.submission().push(&read_e).is_err() {
+                        if ring.submit().is_err() {
+                            break;
+                        }
+                    }
+                }
+
+                let _ = ring.submit();
+            } else {
+                let _ = ring.submit_and_wait(1);
+            }
+
+            while let Some(cqe) = ring.completion().next() {
+                let Some((mut buf, reply_tx)) = inflight.remove(&cqe.user_data()) else {
+                    continue;
+                };
+
+                let result = cqe.result();
+                let requested = buf.len();
+
+                let reply = if result < 0 {
+                    Err(IoError::Os(std::io::Error::from_raw_os_error(-result)))
+                } else if result == 0 {
+                    Err(IoError::Eof)
+                } else if (result as usize) < requested {
+                    Err(IoError::ShortRead {
+                        expected: requested,
+                        actual: result as usize,
+                    })
+                } else {
+                    buf.truncate(result as usize);
+                    Ok(Block::Owned(buf))
+                };
+
+                let _ = reply_tx.send(reply);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl IoEngine for AsyncIoEngine {
+    fn len(&self) -> usize {
+        self.file_len
+    }
+
+    fn read_block(&self, offset: usize, len: usize) -> Result<Block, IoError> {
+        // A zero-length read at or before EOF succeeds, as in `SyncIoEngine`;
+        // io_uring would report it as a 0-byte result, i.e. `Eof`.
+        if len == 0 && offset <= self.file_len {
+            return Ok(Block::Owned(Vec::new()));
+        }
+
+        let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+
+        self.request_tx
+            .send(IoRequest { offset, len, reply_tx })
+            .map_err(|_| IoError::Os(std::io::Error::other("io_uring thread hung up")))?;
+
+        reply_rx
+            .recv()
+            .map_err(|_| IoError::Os(std::io::Error::other("io_uring thread hung up")))?
+    }
+}
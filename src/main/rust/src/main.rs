@@ -1,26 +1,22 @@
-#![feature(int_from_ascii)]
 use core::fmt;
 use std::hash::BuildHasherDefault;
-use std::io::BufRead;
-use std::io::BufReader;
-use std::io::BufWriter;
-use std::io::Write;
-use std::num::ParseIntError;
-use std::ops::Rem;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::sync::TryLockError;
-use std::sync::atomic::AtomicBool;
-use std::thread::sleep;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{error::Error, fs::File};
 
 use hashbrown::HashMap;
 use nohash::NoHashHasher;
 use rayon::Scope;
 use rayon::prelude::ParallelSliceMut;
-use std::sync::atomic::Ordering;
+
+mod checkpoint;
+mod io_engine;
+
+use io_engine::{IoEngine, SyncIoEngine};
+#[cfg(target_os = "linux")]
+use io_engine::AsyncIoEngine;
 
 fn main() {
     if let Err(err) = try_main() {
@@ -31,14 +27,17 @@ fn main() {
 
 fn try_main() -> Result<(), Box<dyn Error>> {
     let home = std::env::home_dir().expect("Could not determine HOME env var");
+    let args = Args::parse(&home);
 
-    let path = std::env::args()
-        .skip(1)
-        .next()
-        .map(|arg| PathBuf::from(arg))
-        .unwrap_or_else(|| home.join("Programming/1brc.data/measurements-1000000000.txt"));
+    let file = File::open(&args.input_path)?;
+    let engine = build_io_engine(file)?;
 
-    let station_map = StationMap::new(path)?;
+    let station_map = StationMap::new(engine)?;
+
+    if let Some(resume_from) = &args.resume_from {
+        let checkpoint = File::open(resume_from)?;
+        station_map.merge_binary(checkpoint)?;
+    }
 
     rayon::in_place_scope(|scope| {
         station_map.exec(scope).unwrap_or_else(|err| {
@@ -47,180 +46,245 @@ fn try_main() -> Result<(), Box<dyn Error>> {
         });
     });
 
-    station_map.read_queue_to_map();
-
     station_map.print_map()?;
 
+    if let Some(checkpoint_out) = &args.checkpoint_out {
+        let checkpoint = File::create(checkpoint_out)?;
+        station_map.write_binary(checkpoint)?;
+    }
+
+    eprintln!(
+        "station-name hash collisions: {}",
+        station_map.collision_count()
+    );
+
     Ok(())
 }
 
+/// Parsed command-line arguments. `--checkpoint-out <path>` writes the
+/// aggregated map out in the versioned binary format after printing, so a
+/// run's results can be resumed or merged elsewhere; `--resume-from <path>`
+/// folds a previously written checkpoint into the map before processing the
+/// input file, letting a billion-row workload be split across machines and
+/// combined later.
+struct Args {
+    input_path: PathBuf,
+    checkpoint_out: Option<PathBuf>,
+    resume_from: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse(home: &std::path::Path) -> Self {
+        let mut input_path = None;
+        let mut checkpoint_out = None;
+        let mut resume_from = None;
+
+        let mut raw_args = std::env::args().skip(1);
+
+        while let Some(arg) = raw_args.next() {
+            match arg.as_str() {
+                "--checkpoint-out" => {
+                    checkpoint_out = raw_args.next().map(PathBuf::from);
+                }
+                "--resume-from" => {
+                    resume_from = raw_args.next().map(PathBuf::from);
+                }
+                _ => input_path = Some(PathBuf::from(arg)),
+            }
+        }
+
+        let input_path = input_path
+            .unwrap_or_else(|| home.join("Programming/1brc.data/measurements-1000000000.txt"));
+
+        Self {
+            input_path,
+            checkpoint_out,
+            resume_from,
+        }
+    }
+}
+
+/// Picks the I/O backend. `SyncIoEngine`'s mmap is the default: it's simpler
+/// and fast enough for most disks. Setting `ONEBRC_IO_ENGINE=uring` opts into
+/// the io_uring-backed `AsyncIoEngine` instead, for NVMe setups where keeping
+/// many reads in flight beats a single mmap's page faults; it's only built on
+/// Linux, so anywhere else this is a no-op. `AsyncIoEngine::new` probes the
+/// ring synchronously, so if it fails to init (old kernel, io_uring disabled
+/// by seccomp/container policy, etc.) that failure is caught right here and
+/// this falls back to `SyncIoEngine` instead of only surfacing once every
+/// `read_block` call starts failing.
+fn build_io_engine(file: File) -> Result<Arc<dyn IoEngine>, Box<dyn Error>> {
+    #[cfg(target_os = "linux")]
+    if std::env::var("ONEBRC_IO_ENGINE").as_deref() == Ok("uring") {
+        const QUEUE_DEPTH: u32 = 128;
+
+        let attempt = file
+            .try_clone()
+            .map_err(io_engine::IoError::from)
+            .and_then(|cloned| AsyncIoEngine::new(cloned, QUEUE_DEPTH));
+
+        match attempt {
+            Ok(engine) => return Ok(Arc::new(engine)),
+            Err(err) => eprintln!("io_uring unavailable ({err}), falling back to mmap"),
+        }
+    }
+
+    Ok(Arc::new(SyncIoEngine::new(&file)?))
+}
+
 struct StationMap {
-    path: PathBuf,
-    map: Mutex<HashMap<u64, Record, BuildHasherDefault<NoHashHasher<u64>>>>,
-    queue: Mutex<Vec<HashMap<u64, Record, BuildHasherDefault<NoHashHasher<u64>>>>>,
-    hangup: AtomicBool,
-    exclusive: AtomicBool,
+    engine: Arc<dyn IoEngine>,
+    map: Mutex<HashMap<u64, StationSlot, BuildHasherDefault<NoHashHasher<u64>>>>,
+    collisions: AtomicU64,
 }
 
 impl StationMap {
-    fn new(path: PathBuf) -> Result<Arc<Self>, Box<dyn Error>> {
+    fn new(engine: Arc<dyn IoEngine>) -> Result<Arc<Self>, Box<dyn Error>> {
         static APPROXIMATE_TOTAL_CAPACITY: usize = 512;
 
         Ok(Arc::new(Self {
-            path,
+            engine,
             map: Mutex::new(HashMap::with_capacity_and_hasher(
                 APPROXIMATE_TOTAL_CAPACITY,
                 nohash::BuildNoHashHasher::new(),
             )),
-            queue: Mutex::new(Vec::with_capacity(APPROXIMATE_TOTAL_CAPACITY)),
-            hangup: AtomicBool::new(false),
-            exclusive: AtomicBool::new(true),
+            collisions: AtomicU64::new(0),
         }))
     }
 
-    fn exec<'a>(self: &Arc<Self>, scope: &Scope) -> Result<(), Box<dyn Error>> {
-        static BUFFER_SIZE: usize = 2_097_152;
-
-        let mut iter_count = 0;
-        let mut total_bytes_read = 0u64;
-
-        let file = File::open(&self.path)?;
-        let file_len = file.metadata()?.len();
-        let near_eof = file_len.saturating_sub(BUFFER_SIZE as u64 * 128);
-
-        let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
-
-        loop {
-            let mut bytes_buffer: Vec<u8> = reader.fill_buf()?.to_vec();
-            reader.consume(bytes_buffer.len());
-            reader.read_until(b'\n', &mut bytes_buffer)?;
-
-            total_bytes_read += bytes_buffer.len() as u64;
-            iter_count += 1;
+    /// Number of distinct station names observed to hash to the same `u64`
+    /// key. A nonzero count doesn't indicate corrupted results (the overflow
+    /// chain in `StationSlot` keeps them separate) but is worth surfacing so
+    /// users can audit their dataset's name cardinality. Only incremented
+    /// once per genuinely new name, at the point it's folded into the
+    /// authoritative global map (never in a worker's local map, which would
+    /// count the same collision again on every thread and checkpoint load).
+    fn collision_count(&self) -> u64 {
+        self.collisions.load(Ordering::Relaxed)
+    }
 
-            if bytes_buffer.is_empty() {
-                break;
-            }
+    fn exec(self: &Arc<Self>, scope: &Scope) -> Result<(), Box<dyn Error>> {
+        let file_len = self.engine.len();
+
+        let num_chunks = rayon::current_num_threads().max(1);
+        let nominal_chunk_len = file_len.div_ceil(num_chunks);
+
+        let mut boundaries = Vec::with_capacity(num_chunks + 1);
+        boundaries.push(0usize);
+
+        for chunk_idx in 1..num_chunks {
+            let nominal_start = (chunk_idx * nominal_chunk_len).min(file_len);
+            let probe_len = (file_len - nominal_start).min(1024 * 1024);
+            let probe = self.engine.read_block(nominal_start, probe_len)?;
+
+            let aligned_start = match probe.as_slice().iter().position(|&byte| byte == b'\n') {
+                Some(newline_offset) => nominal_start + newline_offset + 1,
+                None => file_len,
+            };
+
+            // A line straddling more than one probe window (not expected for
+            // 1BRC-shaped input, but not ruled out either) could otherwise
+            // land this boundary before the previous one, which would
+            // underflow `end - start` in spawn_chunk_worker. Clamping to the
+            // previous boundary keeps `boundaries` non-decreasing by
+            // construction instead of panicking deep in a worker thread.
+            let previous = *boundaries.last().expect("boundaries always has at least one entry");
+            boundaries.push(aligned_start.clamp(previous, file_len));
+        }
 
-            Self::spawn_buffer_worker(self.clone(), bytes_buffer, scope);
+        boundaries.push(file_len);
+        boundaries.dedup();
 
-            if iter_count.rem(128) == 0
-                && total_bytes_read < near_eof
-                && self.exclusive.load(Ordering::SeqCst)
-            {
-                Self::spawn_queue_worker(self.clone(), scope);
-            }
-        }
+        boundaries.windows(2).for_each(|window| {
+            let (start, end) = (window[0], window[1]);
 
-        self.hangup.store(true, Ordering::SeqCst);
+            Self::spawn_chunk_worker(self.clone(), start, end, scope);
+        });
 
         Ok(())
     }
 
-    fn spawn_buffer_worker(self: Arc<Self>, bytes_buffer: Vec<u8>, scope: &Scope) {
+    fn spawn_chunk_worker(self: Arc<Self>, start: usize, end: usize, scope: &Scope) {
         scope.spawn(move |_| {
-            let mut lock_failures = 0u32;
-            let mut local_map: HashMap<u64, Record, BuildHasherDefault<NoHashHasher<u64>>> =
+            let mut local_map: HashMap<u64, StationSlot, BuildHasherDefault<NoHashHasher<u64>>> =
                 HashMap::with_hasher(nohash::BuildNoHashHasher::new());
 
-            unsafe { std::str::from_utf8_unchecked(&bytes_buffer) }
+            let block = self
+                .engine
+                .read_block(start, end - start)
+                .expect("failed to read chunk");
+
+            unsafe { std::str::from_utf8_unchecked(block.as_slice()) }
                 .lines()
-                .filter_map(|line| line.split_once(';'))
-                .filter_map(|(station, temp)| {
-                    parse_i32(temp.as_bytes())
-                        .ok()
-                        .map(|parsed| (station, parsed as i32))
+                .filter_map(|line| {
+                    let delim_offset = line.as_bytes().iter().position(|&b| b == b';')?;
+                    let temp_int = parse_i32(line.as_bytes(), delim_offset);
+
+                    Some((&line[..delim_offset], temp_int))
                 })
                 .for_each(|(station_name, temp_int)| {
                     let uuid = Record::uuid(station_name);
 
                     match local_map.get_mut(&uuid) {
-                        Some(station) => {
-                            station.values.update(temp_int);
+                        Some(slot) => {
+                            slot.update(station_name, temp_int);
                         }
                         None => unsafe {
-                            let item = Record::new(station_name, temp_int);
+                            let slot = StationSlot::new(Record::new(station_name, temp_int));
 
-                            local_map.insert_unique_unchecked(uuid, item);
+                            local_map.insert_unique_unchecked(uuid, slot);
                         },
                     }
                 });
 
-            loop {
-                match self.queue.try_lock() {
-                    Ok(mut locked) => {
-                        locked.push(local_map);
-                        break;
-                    }
-                    Err(err) => {
-                        lock_failures += 1;
-
-                        match err {
-                            TryLockError::Poisoned(_) => panic!("Thread poisoned!"),
-                            TryLockError::WouldBlock => {
-                                let duration = 2u64.pow(lock_failures);
-                                sleep(Duration::from_millis(duration));
-                                continue;
-                            }
+            let Ok(mut map_locked) = self.map.lock() else {
+                panic!("Thread poisoned!")
+            };
+
+            // Collisions are only counted here, against the authoritative
+            // global map: a worker's local map may itself contain a
+            // collision (two distinct names it saw hashing to the same
+            // `uuid`), and that's folded in below via the fresh-insert arm,
+            // but the same collision must never be counted again by another
+            // worker or a later checkpoint merge.
+            local_map
+                .into_iter()
+                .for_each(|(k, v)| match map_locked.get_mut(&k) {
+                    Some(slot) => {
+                        let new_names = slot.merge(&v);
+
+                        if new_names > 0 {
+                            self.collisions
+                                .fetch_add(new_names as u64, Ordering::Relaxed);
                         }
                     }
-                }
-            }
-        });
-    }
-
-    fn spawn_queue_worker(self: Arc<Self>, scope: &Scope) {
-        self.exclusive.store(false, Ordering::SeqCst);
-
-        scope.spawn(move |_| {
-            if self.hangup.load(Ordering::SeqCst) {
-                return;
-            }
-
-            self.read_queue_to_map();
+                    None => {
+                        if v.records.len() > 1 {
+                            self.collisions
+                                .fetch_add((v.records.len() - 1) as u64, Ordering::Relaxed);
+                        }
 
-            self.exclusive.store(true, Ordering::SeqCst);
+                        unsafe {
+                            map_locked.insert_unique_unchecked(k, v);
+                        }
+                    }
+                });
         });
     }
 
-    fn read_queue_to_map(&self) {
-        let mut queue_taken = Vec::new();
-
-        let Ok(mut queue_locked) = self.queue.lock() else {
-            panic!("Thread poisoned!")
-        };
-
-        queue_taken.append(&mut *queue_locked);
-        drop(queue_locked);
-
-        let Ok(mut map_locked) = self.map.lock() else {
-            panic!("Thread poisoned!")
-        };
-
-        queue_taken
-            .into_iter()
-            .flatten()
-            .for_each(|(k, v)| match map_locked.get_mut(&k) {
-                Some(station) => {
-                    station.values.merge(&v.values);
-                }
-                None => unsafe {
-                    map_locked.insert_unique_unchecked(k, v);
-                },
-            });
-    }
-
     fn print_map(&self) -> Result<(), Box<dyn Error>> {
         let out = std::io::stdout();
-        let mut output_buf = BufWriter::new(out);
+        let mut output_buf = std::io::BufWriter::new(out);
         let Ok(map_locked) = self.map.lock() else {
             panic!("Thread poisoned!")
         };
 
-        let mut sorted: Vec<_> = map_locked.values().collect();
+        let mut sorted: Vec<_> = map_locked.values().flat_map(|slot| &slot.records).collect();
         sorted.par_sort_unstable_by(|a, b| a.station_name.cmp(&b.station_name));
 
         {
+            use std::io::Write;
+
             write!(&mut output_buf, "{{")?;
 
             let opt_last = sorted.pop();
@@ -240,12 +304,32 @@ impl StationMap {
 
         Ok(())
     }
+
+    /// Serializes the current map into the versioned, CRC32C-checked
+    /// checkpoint format so a long run can be resumed or merged later.
+    fn write_binary(&self, w: impl std::io::Write) -> Result<(), checkpoint::CheckpointError> {
+        let Ok(map_locked) = self.map.lock() else {
+            panic!("Thread poisoned!")
+        };
+
+        checkpoint::write_binary(&map_locked, w)
+    }
+
+    /// Reads a checkpoint produced by `write_binary`, validating its magic,
+    /// version, and CRC32C, and folds each record into the live map.
+    fn merge_binary(&self, r: impl std::io::Read) -> Result<(), checkpoint::CheckpointError> {
+        let Ok(mut map_locked) = self.map.lock() else {
+            panic!("Thread poisoned!")
+        };
+
+        checkpoint::merge_binary(&mut map_locked, r, &self.collisions)
+    }
 }
 
 #[derive(Clone, Debug)]
-struct Record {
-    station_name: Box<str>,
-    values: StationValues,
+pub(crate) struct Record {
+    pub(crate) station_name: Box<str>,
+    pub(crate) values: StationValues,
 }
 
 impl Record {
@@ -256,7 +340,14 @@ impl Record {
         }
     }
 
-    fn uuid(station_name: &str) -> u64 {
+    pub(crate) fn from_parts(station_name: &str, values: StationValues) -> Self {
+        Self {
+            station_name: station_name.into(),
+            values,
+        }
+    }
+
+    pub(crate) fn uuid(station_name: &str) -> u64 {
         use foldhash::quality::FixedState;
         use std::hash::{BuildHasher, Hasher};
 
@@ -268,6 +359,67 @@ impl Record {
     }
 }
 
+/// Everything stored under one `u64` hash slot.
+///
+/// The fast path is a single-element `records` chain: one lookup, one
+/// comparison against the stored name, done. If two distinct station names
+/// hash to the same `u64` (a foldhash collision), the second name is
+/// appended here instead of silently overwriting the first, so growing
+/// station cardinality can't corrupt results.
+#[derive(Clone, Debug)]
+pub(crate) struct StationSlot {
+    pub(crate) records: Vec<Record>,
+}
+
+impl StationSlot {
+    pub(crate) fn new(record: Record) -> Self {
+        Self {
+            records: vec![record],
+        }
+    }
+
+    pub(crate) fn find_mut(&mut self, station_name: &str) -> Option<&mut Record> {
+        self.records
+            .iter_mut()
+            .find(|record| &*record.station_name == station_name)
+    }
+
+    fn update(&mut self, station_name: &str, temp_int: i32) {
+        match self.find_mut(station_name) {
+            Some(record) => record.values.update(temp_int),
+            None => self.records.push(Record::new(station_name, temp_int)),
+        }
+    }
+
+    /// Folds a single incoming `Record` into this slot, appending it to the
+    /// overflow chain instead of overwriting on a name mismatch. Returns
+    /// `true` when the name wasn't already present, i.e. this slot now
+    /// holds a confirmed collision; callers that track collisions against
+    /// an authoritative map use this to count each one exactly once.
+    pub(crate) fn merge_one(&mut self, incoming: &Record) -> bool {
+        match self.find_mut(&incoming.station_name) {
+            Some(record) => {
+                record.values.merge(&incoming.values);
+                false
+            }
+            None => {
+                self.records.push(incoming.clone());
+                true
+            }
+        }
+    }
+
+    /// Folds every record from `other` into this slot, returning how many
+    /// were genuinely new names sharing this slot's hash.
+    fn merge(&mut self, other: &Self) -> usize {
+        other
+            .records
+            .iter()
+            .filter(|incoming| self.merge_one(incoming))
+            .count()
+    }
+}
+
 impl fmt::Display for Record {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -282,11 +434,15 @@ impl fmt::Display for Record {
 }
 
 #[derive(Clone, Debug, Copy)]
-struct StationValues {
-    min: i32,
-    max: i32,
-    sum: i32,
-    count: u32,
+pub(crate) struct StationValues {
+    pub(crate) min: i32,
+    pub(crate) max: i32,
+    // A billion rows at up to 999 (tenths) each can exceed i32::MAX, so the
+    // running sum is kept as i64 even though every individual reading fits
+    // in i32; this also matches the checkpoint format's `[i64 sum]` field
+    // exactly, rather than widening on write and truncating back on read.
+    pub(crate) sum: i64,
+    pub(crate) count: u32,
 }
 
 impl StationValues {
@@ -294,7 +450,7 @@ impl StationValues {
         Self {
             min: initial_value,
             max: initial_value,
-            sum: initial_value,
+            sum: initial_value as i64,
             count: 1,
         }
     }
@@ -302,7 +458,7 @@ impl StationValues {
     fn update(&mut self, new_value: i32) {
         self.max = std::cmp::max(self.max, new_value);
         self.min = std::cmp::min(self.min, new_value);
-        self.sum += new_value;
+        self.sum += new_value as i64;
         self.count += 1;
     }
 
@@ -326,34 +482,73 @@ impl StationValues {
     }
 }
 
-// Parses ints values between -9999 to 9999
+/// Branchless SWAR decoder for temperatures in the fixed `[-]d{1,3}.d`
+/// format, returning the value scaled by 10 (e.g. `"-12.3"` -> `-123`).
+///
+/// `line` is the full input line and `delim_offset` is the byte index of
+/// the `;` separating the station name from the value. Every value has
+/// exactly one digit after the decimal point, so the `.` always sits two
+/// bytes before the end of the line — its position falls out of
+/// `line.len()` rather than needing to be scanned for. The numeral is
+/// right-aligned into a fixed 8-byte window, zero-padded on the left, so
+/// the same fixed offsets read the sign, the (up to three) integer
+/// digits, and the fractional digit regardless of how many of them are
+/// actually present; a digit that isn't there reads as a zero pad byte,
+/// which `digit_or_zero` masks out instead of contributing to the sum.
 #[inline]
-fn parse_i32(value: &[u8]) -> Result<i32, ParseIntError> {
-    match value {
-        [b'-', h2, h1, h0, b'.', l] => {
-            let val = i32::from_ascii(&[*h2, *h1, *h0, *l])?;
-            Ok(-val)
-        }
-        [b'-', h1, h0, b'.', l] => {
-            let val = i32::from_ascii(&[*h1, *h0, *l])?;
-            Ok(-val)
-        }
-        [b'-', h0, b'.', l] => {
-            let val = i32::from_ascii(&[*h0, *l])?;
-            Ok(-val)
-        }
-        [h2, h1, h0, b'.', l] => {
-            let val = i32::from_ascii(&[*h2, *h1, *h0, *l])?;
-            Ok(val)
-        }
-        [h1, h0, b'.', l] => {
-            let val = i32::from_ascii(&[*h1, *h0, *l])?;
-            Ok(val)
-        }
-        [h0, b'.', l] => {
-            let val = i32::from_ascii(&[*h0, *l])?;
-            Ok(val)
+fn parse_i32(line: &[u8], delim_offset: usize) -> i32 {
+    fn digit_or_zero(byte: u8) -> i32 {
+        let digit = byte.wrapping_sub(b'0') as i32;
+        let is_digit = (digit <= 9) as i32;
+
+        digit * is_digit
+    }
+
+    let numeral = &line[delim_offset + 1..];
+    let len = numeral.len();
+
+    let mut window = [0u8; 8];
+    window[8 - len..].copy_from_slice(numeral);
+
+    // window[6] is always '.' and window[7] the fractional digit; the
+    // optional sign, if present, is always numeral[0] i.e. window[8 - len].
+    let d2 = digit_or_zero(window[3]);
+    let d1 = digit_or_zero(window[4]);
+    let d0 = digit_or_zero(window[5]);
+    let frac = digit_or_zero(window[7]);
+
+    let magnitude = ((d2 * 10 + d1) * 10 + d0) * 10 + frac;
+    let sign_mask = -((window[8 - len] == b'-') as i32);
+
+    (magnitude ^ sign_mask) - sign_mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(temp: &str) -> i32 {
+        let line = format!("Test;{temp}");
+        let delim_offset = line.as_bytes().iter().position(|&b| b == b';').unwrap();
+
+        parse_i32(line.as_bytes(), delim_offset)
+    }
+
+    #[test]
+    fn full_range() {
+        for tenths in -999..=999 {
+            let temp = format!("{:.1}", tenths as f32 / 10.0);
+
+            assert_eq!(parse(&temp), tenths, "temp = {temp}");
         }
-        _ => unreachable!(),
+    }
+
+    #[test]
+    fn single_digit_and_negative_edge_cases() {
+        assert_eq!(parse("0.0"), 0);
+        assert_eq!(parse("9.9"), 99);
+        assert_eq!(parse("-9.9"), -99);
+        assert_eq!(parse("99.9"), 999);
+        assert_eq!(parse("-99.9"), -999);
     }
 }